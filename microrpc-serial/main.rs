@@ -77,6 +77,15 @@ fn execute<C: Read + Write>(line: &str, conn: &mut Client<C>) -> Result<(), Box<
                     arg_values.push(match *arg_type {
                         Type::U8 => Value::U8(arg.parse()?),
                         Type::U16 => Value::U16(arg.parse()?),
+                        Type::I8 => Value::I8(arg.parse()?),
+                        Type::I16 => Value::I16(arg.parse()?),
+                        Type::U32 => Value::U32(arg.parse()?),
+                        Type::I32 => Value::I32(arg.parse()?),
+                        Type::String => Value::String(arg.to_string()),
+                        Type::Bytes | Type::Array(_) => {
+                            return Err(format!("procedure {} takes an argument of type {}, \
+                                which is not supported from the REPL", proc_id, arg_type).into());
+                        }
                     });
                 }
             }
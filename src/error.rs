@@ -46,6 +46,13 @@ pub enum Error {
     ProtocolError {
         description: &'static str,
     },
+
+    /// The channel could not be resynchronized with the Server.
+    ///
+    /// This is returned by `Client::resync` (and by `Client::call`, which calls it automatically)
+    /// when the configured number of resynchronization attempts was exhausted, or when the Server
+    /// came back up exporting a different set of procedures than before.
+    Desynchronized,
 }
 
 impl From<io::Error> for Error {
@@ -71,6 +78,8 @@ impl fmt::Display for Error {
                              other endpoint implements {})", ours, theirs),
             Error::ProtocolError { description } =>
                 writeln!(f, "protocol error: {}", description),
+            Error::Desynchronized =>
+                writeln!(f, "could not resynchronize with the µRPC server"),
         }
     }
 }
@@ -85,6 +94,7 @@ impl error::Error for Error {
             Error::MismatchedArguments { .. } => "mismatched arguments",
             Error::MismatchedVersion { .. } => "mismatched µRPC version",
             Error::ProtocolError { description } => description,
+            Error::Desynchronized => "could not resynchronize with the µRPC server",
         }
     }
 
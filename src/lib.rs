@@ -1,10 +1,14 @@
-//! A Rust implementation of the µRPC protocol (client-side).
+//! A Rust implementation of the µRPC protocol.
 
 extern crate byteorder;
 
 mod error;
+mod server;
+mod state;
 
 pub use error::Error;
+pub use server::{Handler, Server};
+pub use state::{ClientState, Step};
 
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 
@@ -13,22 +17,28 @@ use std::io;
 use std::fmt;
 
 /// Version byte indicating the µRPC protocol version implemented by this library.
-const VERSION: u8 = 0;
+pub(crate) const VERSION: u8 = 0;
 
 /// Result type returned by many functions of this library.
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[repr(u8)]
-enum Request {
+pub(crate) enum Request {
     Version = 0,
     Enumerate = 1,
     Call = 2,
 }
 
+/// Number of times `Client::call` will attempt to resynchronize with the Server before giving up
+/// with `Error::Desynchronized`.
+const DEFAULT_RESYNC_ATTEMPTS: u8 = 3;
+
 /// A client connected to a remote µRPC Server.
 pub struct Client<C: Write + Read> {
     channel: C,
     procedures: Option<Box<[Procedure]>>,
+    resync_attempts: u8,
+    mode: Encoding,
 }
 
 impl<C: Write + Read> Client<C> {
@@ -37,53 +47,42 @@ impl<C: Write + Read> Client<C> {
         Client {
             channel: channel,
             procedures: None,
+            resync_attempts: DEFAULT_RESYNC_ATTEMPTS,
+            mode: Encoding::Fixed,
         }
     }
 
+    /// Gets the `Value` wire encoding last negotiated with the Server via `enumerate`.
+    pub fn encoding(&self) -> Encoding {
+        self.mode
+    }
+
+    /// Sets the number of resynchronization attempts `Client::call` will make after a
+    /// desynchronized channel is detected, before giving up with `Error::Desynchronized`.
+    pub fn set_resync_attempts(&mut self, attempts: u8) {
+        self.resync_attempts = attempts;
+    }
+
     /// Re-Enumerate the Server's exported procedures and store them.
     ///
     /// Since this will be called automatically before anything else is communicated, this also
-    /// checks that the Server's protocol version matches.
+    /// checks that the Server's protocol version matches and negotiates the `Value` wire
+    /// encoding the Server wants to use (see `Client::encoding`).
     pub fn enumerate(&mut self) -> Result<&[Procedure]> {
         // Check version
-        self.channel.write_u8(Request::Version as u8)?;
-        self.read_success()?;
-        let server_version = self.channel.read_u8()?;
+        let mut handshake = ClientState::version_handshake()?;
+        let (server_version, mode) = self.drive(&mut handshake)?;
         if server_version != VERSION {
             return Err(Error::MismatchedVersion {
                 ours: VERSION,
                 theirs: server_version,
             });
         }
+        self.mode = mode;
 
         // Enumerate
-        self.channel.write_u8(Request::Enumerate as u8)?;
-        self.read_success()?;
-        let num_procs = self.channel.read_u16::<NetworkEndian>()?;
-        let mut procs = Vec::with_capacity(num_procs as usize);
-        for i in 0..num_procs {
-            // Read procedure descriptors
-            let byte0 = self.channel.read_u8()?;
-            let has_return_value = byte0 & 0x80 != 0;
-            let num_params = byte0 & 0x7f;
-
-            let return_type = if has_return_value {
-                Some(Type::read(&mut self.channel)?)
-            } else {
-                None
-            };
-
-            let mut params = Vec::with_capacity(num_params as usize);
-            for _ in 0..num_params {
-                params.push(Type::read(&mut self.channel)?);
-            }
-
-            procs.push(Procedure {
-                id: i,
-                parameters: params.into_boxed_slice(),
-                returns: return_type,
-            });
-        }
+        let mut state = ClientState::enumerate()?;
+        let procs = self.drive(&mut state)?;
 
         self.procedures = Some(procs.into_boxed_slice());
 
@@ -103,76 +102,196 @@ impl<C: Write + Read> Client<C> {
     }
 
     /// Calls a procedure.
+    ///
+    /// If the channel appears to have desynchronized (a read timed out or the Server sent an
+    /// unexpected result byte), this transparently attempts to `resync` and retries the call once
+    /// before giving up.
     pub fn call(&mut self, id: u16, arguments: &[Value]) -> Result<Option<Value>> {
-        {
-            let procs = self.procedures()?;
+        match self.call_inner(id, arguments) {
+            Err(ref e) if Self::is_recoverable(e) => {
+                self.resync()?;
+                self.call_inner(id, arguments)
+            }
+            result => result,
+        }
+    }
+
+    fn call_inner(&mut self, id: u16, arguments: &[Value]) -> Result<Option<Value>> {
+        let returns = self.validate_call(id, arguments)?;
+        let mut state = ClientState::call(id, arguments, returns, self.mode)?;
+        self.drive(&mut state)
+    }
 
-            if id as usize >= procs.len() {
-                return Err(Error::ProcOutOfRange);
+    /// Reestablishes protocol synchronization with the Server after the channel has desynced,
+    /// e.g. because of noise on the serial line or the device rebooting.
+    ///
+    /// This drains any bytes still pending in the channel, then re-issues the `Version` handshake
+    /// and re-`enumerate`s, checking that the Server still reports the same procedures it did
+    /// before. Returns `Error::Desynchronized` if this did not succeed within
+    /// `set_resync_attempts` tries. A long-running client (e.g. a REPL) can call this manually to
+    /// recover from a device reboot without restarting the process.
+    pub fn resync(&mut self) -> Result<()> {
+        // Held outside the loop: a failed `enumerate()` inside `try_handshake` never repopulates
+        // `self.procedures`, so re-`take()`ing it on every attempt would lose the list to compare
+        // against as soon as the first attempt failed.
+        let previous = self.procedures.take();
+
+        for _ in 0..self.resync_attempts {
+            self.drain_pending()?;
+
+            if self.try_handshake(previous.as_ref().map(|p| &**p)).is_ok() {
+                return Ok(());
             }
+        }
 
-            let procedure = &procs[id as usize];
-            assert_eq!(procedure.id, id);
-
-            // Make sure all arguments match
-            for (i, (got, expected)) in arguments.iter().zip(procedure.parameters.iter()).enumerate() {
-                if got.ty() != *expected {
-                    assert!(i < 256);
-                    return Err(Error::MismatchedArguments {
-                        index: i as u8,
-                        expected: *expected,
-                        found: got.ty(),
-                    });
-                }
+        Err(Error::Desynchronized)
+    }
+
+    /// Reads and discards bytes until the channel reports no more data is currently available.
+    fn drain_pending(&mut self) -> Result<()> {
+        let mut buf = [0; 64];
+        loop {
+            match self.channel.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(_) => continue,
+                Err(ref e) if Self::is_timeout(e) => return Ok(()),
+                Err(e) => return Err(e.into()),
             }
         }
+    }
+
+    /// Re-runs the `Version`/`Enumerate` handshake, failing if the Server now reports a different
+    /// set of procedures than `previous` (the ones cached from before the desync).
+    fn try_handshake(&mut self, previous: Option<&[Procedure]>) -> Result<()> {
+        let new = self.enumerate()?;
 
-        self.channel.write_u8(Request::Call as u8)?;
-        self.channel.write_u16::<NetworkEndian>(id)?;
-        for arg in arguments {
-            arg.write(&mut self.channel)?;
+        if let Some(previous) = previous {
+            if !procedures_match(previous, new) {
+                return Err(Error::Desynchronized);
+            }
         }
 
-        // Read server response
-        self.read_result()?;
+        Ok(())
+    }
 
-        if let Some(ret_ty) = self.procedures()?[id as usize].returns {
-            let retval = Value::read(&ret_ty, &mut self.channel)?;
-            Ok(Some(retval))
-        } else {
-            Ok(None)
+    /// Whether `err` indicates the channel may have desynchronized and is worth a `resync`.
+    fn is_recoverable(err: &Error) -> bool {
+        match *err {
+            Error::IoError(ref io) => Self::is_timeout(io),
+            Error::ProtocolError { .. } => true,
+            _ => false,
         }
     }
 
-    /// Reads a result byte from the Server.
+    fn is_timeout(err: &io::Error) -> bool {
+        err.kind() == io::ErrorKind::TimedOut || err.kind() == io::ErrorKind::WouldBlock
+    }
+
+    /// Calls several procedures in a row, without waiting for each one's response before sending
+    /// the next request.
     ///
-    /// If the result byte indicates success, returns `Ok(())`. Otherwise, returns an appropriate
-    /// error. If an I/O error occurs, also returns an error.
-    fn read_result(&mut self) -> Result<()> {
-        match self.channel.read_u8()? {
-            0x00 => Ok(()),
-            0x01 => Err(Error::GenericError),
-            _ => Err(Error::ProtocolError {
-                description: "invalid result byte",
-            }),
+    /// This pipelines the requests in `calls` by writing all of them before reading any of the
+    /// responses, which avoids paying a full round-trip per call. The returned `Vec` contains the
+    /// results in the same order as `calls`. Every call's arguments are validated, and every
+    /// request built, before any of them are written to the channel, so a validation failure
+    /// partway through `calls` (a bad id or mismatched argument types) never leaves an earlier
+    /// call's request written with its response left unread on the wire. As with `Client::call`,
+    /// if the channel appears to have desynchronized, this transparently attempts a `resync` and
+    /// retries the whole batch once before giving up; a desync partway through a batch is not
+    /// otherwise recoverable mid-batch, since the pipelining
+    /// means there's no way to tell which of the batch's responses, if any, were actually read.
+    pub fn call_batch(&mut self, calls: &[(u16, &[Value])]) -> Result<Vec<Option<Value>>> {
+        match self.call_batch_inner(calls) {
+            Err(ref e) if Self::is_recoverable(e) => {
+                self.resync()?;
+                self.call_batch_inner(calls)
+            }
+            result => result,
+        }
+    }
+
+    fn call_batch_inner(&mut self, calls: &[(u16, &[Value])]) -> Result<Vec<Option<Value>>> {
+        // Validate every call and build its request before writing any of them: if a later call
+        // in the batch fails validation, nothing has been sent to the Server yet.
+        let mut states = Vec::with_capacity(calls.len());
+        for &(id, arguments) in calls {
+            let returns = self.validate_call(id, arguments)?;
+            states.push(ClientState::call(id, arguments, returns, self.mode)?);
+        }
+
+        for state in &mut states {
+            self.write_state(state)?;
+        }
+
+        states.iter_mut().map(|state| self.drive(state)).collect()
+    }
+
+    /// Validates `arguments` against `id`'s signature, returning the procedure's return type.
+    fn validate_call(&mut self, id: u16, arguments: &[Value]) -> Result<Option<Type>> {
+        let procs = self.procedures()?;
+
+        if id as usize >= procs.len() {
+            return Err(Error::ProcOutOfRange);
+        }
+
+        let procedure = &procs[id as usize];
+        assert_eq!(procedure.id, id);
+
+        if arguments.len() != procedure.parameters.len() {
+            return Err(Error::MismatchedArgumentCount {
+                expected: procedure.parameters.len() as u16,
+                found: arguments.len(),
+            });
+        }
+
+        // Make sure all arguments match
+        for (i, (got, expected)) in arguments.iter().zip(procedure.parameters.iter()).enumerate() {
+            if got.ty() != *expected {
+                assert!(i < 256);
+                return Err(Error::MismatchedArguments {
+                    index: i as u8,
+                    expected: expected.clone(),
+                    found: got.ty(),
+                });
+            }
         }
+
+        Ok(procedure.returns.clone())
     }
 
-    /// Reads a result byte from the server, requiring it to be a success value.
+    /// Writes out everything a `ClientState` has queued up, without reading a response.
     ///
-    /// µRPC specifies that some requests can not fail and must always report a `Success` result. If
-    /// those do fail, the implementation is incorrect.
+    /// Used by `call_batch` to pipeline several requests before reading any of their responses.
+    fn write_state<T>(&mut self, state: &mut ClientState<T>) -> Result<()> {
+        while let Step::NeedWrite(buf) = state.step() {
+            self.channel.write_all(&buf)?;
+            state.wrote(buf.len());
+        }
+
+        Ok(())
+    }
+
+    /// Blocking driver for a `ClientState`, used internally by `Client`'s blocking methods.
     ///
-    /// If the result byte indicates success, returns `Ok(())`. Otherwise, returns a protocol error.
-    /// If an I/O error occurs, also returns an error.
-    fn read_success(&mut self) -> Result<()> {
-        match self.channel.read_u8()? {
-            0x00 => Ok(()),
-            _ => Err(Error::ProtocolError {
-                description: "invalid result of infallible request",
-            }),
+    /// Advanced users who want to integrate µRPC with a non-blocking event loop can drive a
+    /// `ClientState` themselves instead; see the `state` module.
+    fn drive<T>(&mut self, state: &mut ClientState<T>) -> Result<T> {
+        loop {
+            match state.step() {
+                Step::NeedWrite(buf) => {
+                    self.channel.write_all(&buf)?;
+                    state.wrote(buf.len());
+                }
+                Step::NeedRead => {
+                    let mut byte = [0; 1];
+                    self.channel.read_exact(&mut byte)?;
+                    state.feed(&byte);
+                }
+                Step::Done(result) => return result,
+            }
         }
     }
+
 }
 
 /// A callable procedure exported by a µRPC server.
@@ -186,6 +305,15 @@ pub struct Procedure {
 }
 
 impl Procedure {
+    /// Builds a `Procedure` descriptor as parsed off the wire by `Client::enumerate`.
+    pub(crate) fn new(id: u16, parameters: Vec<Type>, returns: Option<Type>) -> Self {
+        Procedure {
+            id: id,
+            parameters: parameters.into_boxed_slice(),
+            returns: returns,
+        }
+    }
+
     /// Gets the ID of the procedure used to call it.
     pub fn id(&self) -> u16 { self.id }
 
@@ -193,24 +321,251 @@ impl Procedure {
     pub fn parameter_types(&self) -> &[Type] { &self.parameters }
 
     /// Gets the type of the return value of this procedure (if there is a return value).
-    pub fn return_type(&self) -> Option<Type> { self.returns }
+    pub fn return_type(&self) -> Option<Type> { self.returns.clone() }
 }
 
-/// Types supported by µRPC.
+/// Compares two procedure lists for equality of id, parameter types and return type, used by
+/// `Client::resync` to detect a Server that came back up with a different set of procedures.
+fn procedures_match(a: &[Procedure], b: &[Procedure]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| {
+        a.id == b.id && a.parameters == b.parameters && a.returns == b.returns
+    })
+}
+
+/// A wire-format piece that can be read from any reader.
+///
+/// This is implemented for the primitives `Type` and `Value` are built out of, so their own
+/// `read`/`write` methods don't have to hand-roll the same `byteorder` calls for every variant.
+pub(crate) trait Readable: Sized {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self>;
+}
+
+/// A wire-format piece that can be written to any writer.
+pub(crate) trait Writeable {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()>;
+}
+
+impl Readable for u8 {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> { Ok(r.read_u8()?) }
+}
+impl Writeable for u8 {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> { w.write_u8(*self) }
+}
+
+impl Readable for i8 {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> { Ok(r.read_i8()?) }
+}
+impl Writeable for i8 {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> { w.write_i8(*self) }
+}
+
+impl Readable for u16 {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> { Ok(r.read_u16::<NetworkEndian>()?) }
+}
+impl Writeable for u16 {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> { w.write_u16::<NetworkEndian>(*self) }
+}
+
+impl Readable for i16 {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> { Ok(r.read_i16::<NetworkEndian>()?) }
+}
+impl Writeable for i16 {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> { w.write_i16::<NetworkEndian>(*self) }
+}
+
+impl Readable for u32 {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> { Ok(r.read_u32::<NetworkEndian>()?) }
+}
+impl Writeable for u32 {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> { w.write_u32::<NetworkEndian>(*self) }
+}
+
+impl Readable for i32 {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> { Ok(r.read_i32::<NetworkEndian>()?) }
+}
+impl Writeable for i32 {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> { w.write_i32::<NetworkEndian>(*self) }
+}
+
+impl Readable for Vec<u8> {
+    /// Reads a `u16` length prefix followed by that many raw bytes.
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let len = u16::read_from(r)?;
+        let mut buf = vec![0; len as usize];
+        r.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+}
+impl Writeable for Vec<u8> {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (self.len() as u16).write_to(w)?;
+        w.write_all(self)
+    }
+}
+
+impl Readable for String {
+    /// Reads a `u16` length prefix followed by that many bytes of UTF-8.
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let bytes = Vec::<u8>::read_from(r)?;
+        String::from_utf8(bytes).map_err(|_| Error::ProtocolError {
+            description: "string is not valid utf-8",
+        })
+    }
+}
+impl Writeable for String {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (self.len() as u16).write_to(w)?;
+        w.write_all(self.as_bytes())
+    }
+}
+
+/// Wire encoding used for the integers and array element counts carried by `Value`s, negotiated
+/// by the `Version` handshake. The length prefixes of `String` and `Bytes` values are fixed-width
+/// `u16`s in either mode.
 #[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Encoding {
+    /// The original fixed-width, network-endian encoding.
+    Fixed,
+    /// Compact varint/zigzag encoding, which shrinks small-magnitude values at the cost of a
+    /// variable-length wire representation.
+    Compact,
+}
+
+impl Encoding {
+    /// Reads the `Encoding` byte the Server advertises after the `Version` byte.
+    pub(crate) fn read<R: Read>(r: &mut R) -> Result<Self> {
+        Ok(match r.read_u8()? {
+            0x00 => Encoding::Fixed,
+            0x01 => Encoding::Compact,
+            _ => return Err(Error::ProtocolError {
+                description: "invalid encoding mode",
+            }),
+        })
+    }
+
+    /// Writes the `Encoding` byte advertised after the `Version` byte.
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u8(match *self {
+            Encoding::Fixed => 0x00,
+            Encoding::Compact => 0x01,
+        })
+    }
+}
+
+/// Writes `val` as an unsigned LEB128 varint: 7 bits per byte, little-endian, with the
+/// continuation bit `0x80` set on every byte but the last.
+fn write_varint<W: Write>(mut val: u64, w: &mut W) -> io::Result<()> {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            return w.write_u8(byte);
+        }
+        w.write_u8(byte | 0x80)?;
+    }
+}
+
+/// Reads an unsigned LEB128 varint, erroring if the decoded value does not fit in `max_bits`
+/// bits.
+fn read_varint<R: Read>(r: &mut R, max_bits: u32) -> Result<u64> {
+    let mut result: u64 = 0;
+    let mut shift: u32 = 0;
+    loop {
+        if shift >= 64 {
+            return Err(Error::ProtocolError {
+                description: "varint is too long",
+            });
+        }
+
+        let byte = r.read_u8()?;
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+
+    if max_bits < 64 && result >> max_bits != 0 {
+        return Err(Error::ProtocolError {
+            description: "varint overflows target width",
+        });
+    }
+
+    Ok(result)
+}
+
+/// Zigzag-encodes a signed 8-bit integer so small-magnitude negatives stay small after
+/// varint-encoding.
+fn zigzag_encode_i8(n: i8) -> u8 { ((n << 1) ^ (n >> 7)) as u8 }
+/// Inverse of `zigzag_encode_i8`.
+fn zigzag_decode_i8(u: u8) -> i8 { ((u >> 1) as i8) ^ -((u & 1) as i8) }
+
+/// Zigzag-encodes a signed 16-bit integer so small-magnitude negatives stay small after
+/// varint-encoding.
+fn zigzag_encode_i16(n: i16) -> u16 { ((n << 1) ^ (n >> 15)) as u16 }
+/// Inverse of `zigzag_encode_i16`.
+fn zigzag_decode_i16(u: u16) -> i16 { ((u >> 1) as i16) ^ -((u & 1) as i16) }
+
+/// Zigzag-encodes a signed 32-bit integer so small-magnitude negatives stay small after
+/// varint-encoding.
+fn zigzag_encode_i32(n: i32) -> u32 { ((n << 1) ^ (n >> 31)) as u32 }
+/// Inverse of `zigzag_encode_i32`.
+fn zigzag_decode_i32(u: u32) -> i32 { ((u >> 1) as i32) ^ -((u & 1) as i32) }
+
+/// Types supported by µRPC.
+#[derive(Debug, PartialEq, Clone)]
 pub enum Type {
-    /// 8-bit integer.
+    /// Unsigned 8-bit integer.
     U8,
-    /// 16-bit integer.
+    /// Unsigned 16-bit integer.
     U16,
+    /// Signed 8-bit integer.
+    I8,
+    /// Signed 16-bit integer.
+    I16,
+    /// Unsigned 32-bit integer.
+    U32,
+    /// Signed 32-bit integer.
+    I32,
+    /// Length-prefixed UTF-8 string.
+    String,
+    /// Length-prefixed byte array.
+    Bytes,
+    /// Homogeneous array of another `Type`, length-prefixed.
+    Array(Box<Type>),
+}
+
+/// Maximum nesting depth accepted when reading a `Type::Array`.
+///
+/// Bounds the recursion in `Type::read_from_depth` so a corrupted stream that keeps delivering
+/// the `Array` tag byte returns a protocol error instead of overflowing the stack.
+const MAX_ARRAY_DEPTH: u32 = 32;
+
+impl Readable for Type {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        Type::read_from_depth(r, 0)
+    }
 }
 
 impl Type {
-    /// Reads a `Type` encoded as specified in the µRPC protocol.
-    fn read<R: Read>(r: &mut R) -> Result<Self> {
-        Ok(match r.read_u8()? {
+    fn read_from_depth<R: Read>(r: &mut R, depth: u32) -> Result<Self> {
+        Ok(match u8::read_from(r)? {
             0x00 => Type::U8,
             0x01 => Type::U16,
+            0x02 => Type::I8,
+            0x03 => Type::I16,
+            0x04 => Type::U32,
+            0x05 => Type::I32,
+            0x06 => Type::String,
+            0x07 => Type::Bytes,
+            0x08 => {
+                if depth >= MAX_ARRAY_DEPTH {
+                    return Err(Error::ProtocolError {
+                        description: "array nesting is too deep",
+                    });
+                }
+                Type::Array(Box::new(Type::read_from_depth(r, depth + 1)?))
+            }
             _ => return Err(Error::ProtocolError {
                 description: "invalid type",
             }),
@@ -218,55 +573,175 @@ impl Type {
     }
 }
 
+impl Writeable for Type {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match *self {
+            Type::U8 => 0x00u8.write_to(w),
+            Type::U16 => 0x01u8.write_to(w),
+            Type::I8 => 0x02u8.write_to(w),
+            Type::I16 => 0x03u8.write_to(w),
+            Type::U32 => 0x04u8.write_to(w),
+            Type::I32 => 0x05u8.write_to(w),
+            Type::String => 0x06u8.write_to(w),
+            Type::Bytes => 0x07u8.write_to(w),
+            Type::Array(ref elem) => {
+                0x08u8.write_to(w)?;
+                elem.write_to(w)
+            }
+        }
+    }
+}
+
+impl Type {
+    /// Reads a `Type` encoded as specified in the µRPC protocol.
+    pub(crate) fn read<R: Read>(r: &mut R) -> Result<Self> {
+        <Self as Readable>::read_from(r)
+    }
+
+    /// Writes this `Type` as specified in the µRPC protocol.
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.write_to(w)
+    }
+}
+
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Type::U8 => write!(f, "u8"),
             Type::U16 => write!(f, "u16"),
+            Type::I8 => write!(f, "i8"),
+            Type::I16 => write!(f, "i16"),
+            Type::U32 => write!(f, "u32"),
+            Type::I32 => write!(f, "i32"),
+            Type::String => write!(f, "string"),
+            Type::Bytes => write!(f, "bytes"),
+            Type::Array(ref elem) => write!(f, "[{}]", elem),
         }
     }
 }
 
 /// µRPC values, returned by procedures and passed as arguments.
 pub enum Value {
-    /// 8-bit integer.
+    /// Unsigned 8-bit integer.
     U8(u8),
-    /// 16-bit integer.
+    /// Unsigned 16-bit integer.
     U16(u16),
+    /// Signed 8-bit integer.
+    I8(i8),
+    /// Signed 16-bit integer.
+    I16(i16),
+    /// Unsigned 32-bit integer.
+    U32(u32),
+    /// Signed 32-bit integer.
+    I32(i32),
+    /// UTF-8 string.
+    String(String),
+    /// Raw byte array.
+    Bytes(Vec<u8>),
+    /// Homogeneous array of values of the given element `Type`.
+    Array(Type, Vec<Value>),
 }
 
 impl Value {
-    /// Reads a `Value` of given `Type` from a reader.
-    fn read<R: Read>(ty: &Type, r: &mut R) -> io::Result<Self> {
+    /// Reads a `Value` of given `Type` from a reader, using the negotiated `mode` to decode
+    /// integers and counts.
+    pub(crate) fn read<R: Read>(ty: &Type, mode: Encoding, r: &mut R) -> Result<Self> {
         Ok(match *ty {
-            Type::U8 => {
-                let mut buf = [0];
-                r.read_exact(&mut buf)?;
-                Value::U8(buf[0])
-            }
-            Type::U16 => {
-                let mut buf = [0, 0];
-                r.read_exact(&mut buf)?;
-
-                let (msb, lsb) = (buf[0] as u16, buf[1] as u16);
-                Value::U16(msb << 8 | lsb)
+            Type::U8 => Value::U8(match mode {
+                Encoding::Fixed => u8::read_from(r)?,
+                Encoding::Compact => read_varint(r, 8)? as u8,
+            }),
+            Type::U16 => Value::U16(match mode {
+                Encoding::Fixed => u16::read_from(r)?,
+                Encoding::Compact => read_varint(r, 16)? as u16,
+            }),
+            Type::I8 => Value::I8(match mode {
+                Encoding::Fixed => i8::read_from(r)?,
+                Encoding::Compact => zigzag_decode_i8(read_varint(r, 8)? as u8),
+            }),
+            Type::I16 => Value::I16(match mode {
+                Encoding::Fixed => i16::read_from(r)?,
+                Encoding::Compact => zigzag_decode_i16(read_varint(r, 16)? as u16),
+            }),
+            Type::U32 => Value::U32(match mode {
+                Encoding::Fixed => u32::read_from(r)?,
+                Encoding::Compact => read_varint(r, 32)? as u32,
+            }),
+            Type::I32 => Value::I32(match mode {
+                Encoding::Fixed => i32::read_from(r)?,
+                Encoding::Compact => zigzag_decode_i32(read_varint(r, 32)? as u32),
+            }),
+            Type::String => Value::String(String::read_from(r)?),
+            Type::Bytes => Value::Bytes(Vec::<u8>::read_from(r)?),
+            Type::Array(ref elem_ty) => {
+                let len = match mode {
+                    Encoding::Fixed => u16::read_from(r)?,
+                    Encoding::Compact => read_varint(r, 16)? as u16,
+                };
+                let mut values = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    values.push(Value::read(elem_ty, mode, r)?);
+                }
+                Value::Array((**elem_ty).clone(), values)
             }
         })
     }
 
-    /// Writes this `Value` for transmission according to the µRPC protocol.
-    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    /// Writes this `Value` for transmission according to the µRPC protocol, using the negotiated
+    /// `mode` to encode integers and counts.
+    pub(crate) fn write<W: Write>(&self, mode: Encoding, w: &mut W) -> io::Result<()> {
         match *self {
-            Value::U8(val) => w.write_u8(val),
-            Value::U16(val) => w.write_u16::<NetworkEndian>(val),
+            Value::U8(val) => match mode {
+                Encoding::Fixed => val.write_to(w),
+                Encoding::Compact => write_varint(val as u64, w),
+            },
+            Value::U16(val) => match mode {
+                Encoding::Fixed => val.write_to(w),
+                Encoding::Compact => write_varint(val as u64, w),
+            },
+            Value::I8(val) => match mode {
+                Encoding::Fixed => val.write_to(w),
+                Encoding::Compact => write_varint(zigzag_encode_i8(val) as u64, w),
+            },
+            Value::I16(val) => match mode {
+                Encoding::Fixed => val.write_to(w),
+                Encoding::Compact => write_varint(zigzag_encode_i16(val) as u64, w),
+            },
+            Value::U32(val) => match mode {
+                Encoding::Fixed => val.write_to(w),
+                Encoding::Compact => write_varint(val as u64, w),
+            },
+            Value::I32(val) => match mode {
+                Encoding::Fixed => val.write_to(w),
+                Encoding::Compact => write_varint(zigzag_encode_i32(val) as u64, w),
+            },
+            Value::String(ref val) => val.write_to(w),
+            Value::Bytes(ref val) => val.write_to(w),
+            Value::Array(_, ref values) => {
+                match mode {
+                    Encoding::Fixed => (values.len() as u16).write_to(w)?,
+                    Encoding::Compact => write_varint(values.len() as u64, w)?,
+                }
+                for val in values {
+                    val.write(mode, w)?;
+                }
+                Ok(())
+            }
         }
     }
 
     /// Gets the type of this `Value`.
-    fn ty(&self) -> Type {
+    pub(crate) fn ty(&self) -> Type {
         match *self {
             Value::U8(_) => Type::U8,
             Value::U16(_) => Type::U16,
+            Value::I8(_) => Type::I8,
+            Value::I16(_) => Type::I16,
+            Value::U32(_) => Type::U32,
+            Value::I32(_) => Type::I32,
+            Value::String(_) => Type::String,
+            Value::Bytes(_) => Type::Bytes,
+            Value::Array(ref elem_ty, _) => Type::Array(Box::new(elem_ty.clone())),
         }
     }
 }
@@ -276,6 +751,70 @@ impl fmt::Display for Value {
         match *self {
             Value::U8(i) => write!(f, "{}", i),
             Value::U16(i) => write!(f, "{}", i),
+            Value::I8(i) => write!(f, "{}", i),
+            Value::I16(i) => write!(f, "{}", i),
+            Value::U32(i) => write!(f, "{}", i),
+            Value::I32(i) => write!(f, "{}", i),
+            Value::String(ref s) => write!(f, "{:?}", s),
+            Value::Bytes(ref b) => write!(f, "{:?}", b),
+            Value::Array(_, ref values) => {
+                write!(f, "[")?;
+                for (i, val) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", val)?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
+
+/// A `Client` using a [`Buffered`](struct.Buffered.html) transport.
+pub type BufferedClient<C> = Client<Buffered<C>>;
+
+/// A transport wrapper that coalesces outgoing bytes into a single buffered write.
+///
+/// Every write `Client` performs (the request byte, id and argument bytes) is appended to an
+/// internal buffer instead of being sent immediately. The buffer is flushed to the wrapped
+/// channel in one go, either explicitly via `Write::flush` or automatically the next time bytes
+/// are read. Combined with `Client::call_batch`, this coalesces a whole batch of calls into a
+/// single write, which saves a full round-trip per call over slow links such as a serial port.
+pub struct Buffered<C: Read + Write> {
+    channel: C,
+    buf: Vec<u8>,
+}
+
+impl<C: Read + Write> Buffered<C> {
+    /// Wraps `channel`, buffering writes until they are flushed.
+    pub fn new(channel: C) -> Self {
+        Buffered {
+            channel: channel,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl<C: Read + Write> Write for Buffered<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.channel.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+
+        self.channel.flush()
+    }
+}
+
+impl<C: Read + Write> Read for Buffered<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush()?;
+        self.channel.read(buf)
+    }
+}
@@ -0,0 +1,430 @@
+//! Server-side implementation of the µRPC protocol.
+
+use {Encoding, Error, Request, Result, Type, Value, VERSION};
+
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+
+use std::io::prelude::*;
+
+/// A procedure exported by a [`Server`](struct.Server.html), along with the closure invoked to
+/// handle calls to it.
+pub struct Handler {
+    parameters: Box<[Type]>,
+    returns: Option<Type>,
+    callback: Box<Fn(&[Value]) -> Result<Option<Value>>>,
+}
+
+impl Handler {
+    /// Creates a new `Handler` for a procedure taking `parameters` and optionally returning
+    /// `returns`, invoking `callback` whenever the procedure is called.
+    pub fn new<F>(parameters: Vec<Type>, returns: Option<Type>, callback: F) -> Self
+        where F: Fn(&[Value]) -> Result<Option<Value>> + 'static
+    {
+        Handler {
+            parameters: parameters.into_boxed_slice(),
+            returns: returns,
+            callback: Box::new(callback),
+        }
+    }
+}
+
+/// A server exporting procedures to a remote µRPC `Client` over `channel`.
+///
+/// A `Server` dispatches the `Version`, `Enumerate` and `Call` requests understood by `Client` to
+/// a registry of [`Handler`]s, encoding exactly the wire format `Client::enumerate` parses.
+///
+/// [`Handler`]: struct.Handler.html
+pub struct Server<C: Write + Read> {
+    channel: C,
+    handlers: Vec<Handler>,
+    mode: Encoding,
+}
+
+impl<C: Write + Read> Server<C> {
+    /// Creates a new `Server` object using `channel` for communication with the µRPC client.
+    pub fn new(channel: C) -> Self {
+        Server {
+            channel: channel,
+            handlers: Vec::new(),
+            mode: Encoding::Fixed,
+        }
+    }
+
+    /// Sets the `Value` wire encoding this `Server` advertises to the Client during the
+    /// `Version` handshake.
+    pub fn set_encoding(&mut self, mode: Encoding) {
+        self.mode = mode;
+    }
+
+    /// Registers a procedure, returning the id a `Client` must use to call it.
+    pub fn register(&mut self, handler: Handler) -> u16 {
+        assert!(self.handlers.len() < 0x10000, "too many registered procedures");
+        self.handlers.push(handler);
+        (self.handlers.len() - 1) as u16
+    }
+
+    /// Reads and dispatches a single request from `channel`, blocking until one arrives.
+    pub fn serve_one(&mut self) -> Result<()> {
+        match self.channel.read_u8()? {
+            b if b == Request::Version as u8 => self.handle_version(),
+            b if b == Request::Enumerate as u8 => self.handle_enumerate(),
+            b if b == Request::Call as u8 => self.handle_call(),
+            _ => Err(Error::ProtocolError {
+                description: "invalid request byte",
+            }),
+        }
+    }
+
+    fn handle_version(&mut self) -> Result<()> {
+        self.channel.write_u8(0x00)?;
+        self.channel.write_u8(VERSION)?;
+        self.mode.write(&mut self.channel)?;
+        Ok(())
+    }
+
+    fn handle_enumerate(&mut self) -> Result<()> {
+        self.channel.write_u8(0x00)?;
+        self.channel.write_u16::<NetworkEndian>(self.handlers.len() as u16)?;
+
+        for handler in &self.handlers {
+            let byte0 = if handler.returns.is_some() { 0x80 } else { 0x00 }
+                | (handler.parameters.len() as u8 & 0x7f);
+            self.channel.write_u8(byte0)?;
+
+            if let Some(ref ret) = handler.returns {
+                ret.write(&mut self.channel)?;
+            }
+
+            for param in handler.parameters.iter() {
+                param.write(&mut self.channel)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_call(&mut self) -> Result<()> {
+        let id = self.channel.read_u16::<NetworkEndian>()?;
+
+        let handler = match self.handlers.get(id as usize) {
+            Some(handler) => handler,
+            None => {
+                // We don't know the parameter types, so we can't keep reading the argument
+                // bytes without desyncing the stream. The caller is expected to only call
+                // procedures it got from `Enumerate`, so this should not normally happen.
+                self.channel.write_u8(0x01)?;
+                return Ok(());
+            }
+        };
+
+        let mut arguments = Vec::with_capacity(handler.parameters.len());
+        for ty in handler.parameters.iter() {
+            arguments.push(Value::read(ty, self.mode, &mut self.channel)?);
+        }
+
+        match (handler.callback)(&arguments) {
+            Ok(retval) => {
+                self.channel.write_u8(0x00)?;
+                if let Some(ref retval) = retval {
+                    retval.write(self.mode, &mut self.channel)?;
+                }
+            }
+            Err(_) => {
+                self.channel.write_u8(0x01)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Handler, Server};
+    use {Client, Encoding, Type, Value};
+
+    use std::io;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Default `MemoryChannel` read timeout, generous enough that the round-trip tests (which
+    /// never expect it to fire) are never flaky.
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// An in-memory, in-process duplex channel backed by a pair of byte queues, for pairing a
+    /// `Client` and a `Server` in tests without any real I/O.
+    struct MemoryChannel {
+        tx: Sender<u8>,
+        rx: Receiver<u8>,
+        timeout: Duration,
+    }
+
+    /// Creates two ends of an in-memory channel, each other's peer.
+    fn memory_channel_pair() -> (MemoryChannel, MemoryChannel) {
+        memory_channel_pair_with_timeouts(DEFAULT_TIMEOUT, DEFAULT_TIMEOUT)
+    }
+
+    /// Like `memory_channel_pair`, but lets each end's read timeout be set independently. Used to
+    /// give a `Client` under test a short timeout (so `drain_pending`/`resync` can be exercised
+    /// without a long wait) while its scripted peer keeps a generous one, since it is never
+    /// expected to actually see its reads time out.
+    fn memory_channel_pair_with_timeouts(a_timeout: Duration, b_timeout: Duration) -> (MemoryChannel, MemoryChannel) {
+        let (tx_a, rx_a) = mpsc::channel();
+        let (tx_b, rx_b) = mpsc::channel();
+        (
+            MemoryChannel { tx: tx_a, rx: rx_b, timeout: a_timeout },
+            MemoryChannel { tx: tx_b, rx: rx_a, timeout: b_timeout },
+        )
+    }
+
+    impl io::Read for MemoryChannel {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+
+            // Wait for at least one byte to become available, then grab whatever else is ready
+            // without blocking further. A peer that never writes looks like a timed-out serial
+            // read, which is what lets `Client::drain_pending`/`resync` be exercised in tests.
+            buf[0] = match self.rx.recv_timeout(self.timeout) {
+                Ok(byte) => byte,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "no data available"));
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "peer disconnected"));
+                }
+            };
+
+            let mut read = 1;
+            while read < buf.len() {
+                match self.rx.try_recv() {
+                    Ok(byte) => {
+                        buf[read] = byte;
+                        read += 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            Ok(read)
+        }
+    }
+
+    impl io::Write for MemoryChannel {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for &byte in buf {
+                self.tx.send(byte)
+                    .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "peer disconnected"))?;
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn client_server_round_trip() {
+        let (client_chan, server_chan) = memory_channel_pair();
+
+        let server_thread = thread::spawn(move || {
+            let mut server = Server::new(server_chan);
+            server.register(Handler::new(vec![Type::U8, Type::U8], Some(Type::U8), |args| {
+                let a = match args[0] { Value::U8(a) => a, _ => unreachable!() };
+                let b = match args[1] { Value::U8(b) => b, _ => unreachable!() };
+                Ok(Some(Value::U8(a + b)))
+            }));
+
+            // One request each for the Version handshake, the Enumerate and the Call below.
+            for _ in 0..3 {
+                server.serve_one().unwrap();
+            }
+        });
+
+        let mut client = Client::new(client_chan);
+
+        let procs = client.enumerate().unwrap();
+        assert_eq!(procs.len(), 1);
+        assert_eq!(procs[0].parameter_types().to_vec(), vec![Type::U8, Type::U8]);
+        assert_eq!(procs[0].return_type(), Some(Type::U8));
+
+        match client.call(0, &[Value::U8(2), Value::U8(3)]).unwrap() {
+            Some(Value::U8(sum)) => assert_eq!(sum, 5),
+            other => panic!("unexpected call result: {:?}", other.map(|v| v.to_string())),
+        }
+
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn call_batch_pipelines_requests() {
+        let (client_chan, server_chan) = memory_channel_pair();
+
+        let server_thread = thread::spawn(move || {
+            let mut server = Server::new(server_chan);
+            server.register(Handler::new(vec![Type::U8, Type::U8], Some(Type::U8), |args| {
+                let a = match args[0] { Value::U8(a) => a, _ => unreachable!() };
+                let b = match args[1] { Value::U8(b) => b, _ => unreachable!() };
+                Ok(Some(Value::U8(a + b)))
+            }));
+
+            // Version, Enumerate, then one request per call in the batch below.
+            for _ in 0..4 {
+                server.serve_one().unwrap();
+            }
+        });
+
+        let mut client = Client::new(client_chan);
+
+        let calls = [
+            (0u16, &[Value::U8(2), Value::U8(3)][..]),
+            (0u16, &[Value::U8(10), Value::U8(20)][..]),
+        ];
+        let results = client.call_batch(&calls).unwrap();
+
+        match results[0] {
+            Some(Value::U8(sum)) => assert_eq!(sum, 5),
+            ref other => panic!("unexpected result for first call: {:?}", other.as_ref().map(|v| v.to_string())),
+        }
+        match results[1] {
+            Some(Value::U8(sum)) => assert_eq!(sum, 30),
+            ref other => panic!("unexpected result for second call: {:?}", other.as_ref().map(|v| v.to_string())),
+        }
+
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn resync_recovers_from_noise_on_the_line() {
+        // The client gets a short read timeout, so `drain_pending` doesn't have to wait long to
+        // decide the noise has been fully drained; the scripted peer keeps a generous one.
+        let (client_chan, server_chan) =
+            memory_channel_pair_with_timeouts(Duration::from_millis(30), DEFAULT_TIMEOUT);
+
+        // A hand-scripted peer, rather than a real `Server`, so a single stray byte can be
+        // injected into the middle of the first call's response to simulate line noise.
+        let script_thread = thread::spawn(move || {
+            let mut server_chan = server_chan;
+
+            let mut byte = [0; 1];
+            io::Read::read_exact(&mut server_chan, &mut byte).unwrap();
+            assert_eq!(byte[0], 0); // Version
+            io::Write::write_all(&mut server_chan, &[0x00, 0x00, 0x00]).unwrap();
+
+            io::Read::read_exact(&mut server_chan, &mut byte).unwrap();
+            assert_eq!(byte[0], 1); // Enumerate
+            // One no-argument procedure returning a U8.
+            io::Write::write_all(&mut server_chan, &[0x00, 0x00, 0x01, 0x80, 0x00]).unwrap();
+
+            let mut call = [0; 3];
+            io::Read::read_exact(&mut server_chan, &mut call).unwrap();
+            assert_eq!(call[0], 2); // Call
+            // A bogus result byte (noise), followed by what would have been the real response.
+            // `Client::resync`'s `drain_pending` is expected to read and discard the latter.
+            io::Write::write_all(&mut server_chan, &[0xff, 0x00, 0x2a]).unwrap();
+
+            // resync() re-runs the handshake, reporting the same single procedure.
+            io::Read::read_exact(&mut server_chan, &mut byte).unwrap();
+            assert_eq!(byte[0], 0);
+            io::Write::write_all(&mut server_chan, &[0x00, 0x00, 0x00]).unwrap();
+
+            io::Read::read_exact(&mut server_chan, &mut byte).unwrap();
+            assert_eq!(byte[0], 1);
+            io::Write::write_all(&mut server_chan, &[0x00, 0x00, 0x01, 0x80, 0x00]).unwrap();
+
+            // The retried call gets a clean response this time.
+            io::Read::read_exact(&mut server_chan, &mut call).unwrap();
+            assert_eq!(call[0], 2);
+            io::Write::write_all(&mut server_chan, &[0x00, 0x2a]).unwrap();
+        });
+
+        let mut client = Client::new(client_chan);
+        match client.call(0, &[]) {
+            Ok(Some(Value::U8(val))) => assert_eq!(val, 0x2a),
+            other => panic!("expected the call to recover via resync, got {:?}", other.map(|v| v.map(|v| v.to_string()))),
+        }
+
+        script_thread.join().unwrap();
+    }
+
+    #[test]
+    fn strings_arrays_and_signed_ints_round_trip() {
+        let (client_chan, server_chan) = memory_channel_pair();
+
+        let server_thread = thread::spawn(move || {
+            let mut server = Server::new(server_chan);
+            server.register(Handler::new(
+                vec![Type::String, Type::Array(Box::new(Type::I16))],
+                Some(Type::Array(Box::new(Type::I8))),
+                |args| {
+                    let name = match args[0] { Value::String(ref s) => s.clone(), _ => unreachable!() };
+                    let nums = match args[1] {
+                        Value::Array(_, ref vals) => vals.iter().map(|v| match *v {
+                            Value::I16(n) => n,
+                            _ => unreachable!(),
+                        }).collect::<Vec<_>>(),
+                        _ => unreachable!(),
+                    };
+
+                    assert_eq!(name, "hello");
+                    assert_eq!(nums, vec![-300, 0, 300]);
+
+                    Ok(Some(Value::Array(Type::I8, vec![Value::I8(-1), Value::I8(2)])))
+                },
+            ));
+
+            for _ in 0..3 {
+                server.serve_one().unwrap();
+            }
+        });
+
+        let mut client = Client::new(client_chan);
+
+        let name = Value::String("hello".to_owned());
+        let nums = Value::Array(Type::I16, vec![Value::I16(-300), Value::I16(0), Value::I16(300)]);
+        match client.call(0, &[name, nums]).unwrap() {
+            Some(Value::Array(Type::I8, values)) => {
+                let values: Vec<i8> = values.into_iter().map(|v| match v {
+                    Value::I8(n) => n,
+                    _ => unreachable!(),
+                }).collect();
+                assert_eq!(values, vec![-1, 2]);
+            }
+            other => panic!("unexpected call result: {:?}", other.map(|v| v.to_string())),
+        }
+
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn compact_encoding_round_trip() {
+        let (client_chan, server_chan) = memory_channel_pair();
+
+        let server_thread = thread::spawn(move || {
+            let mut server = Server::new(server_chan);
+            server.set_encoding(Encoding::Compact);
+            server.register(Handler::new(vec![Type::I32], Some(Type::I32), |args| {
+                let n = match args[0] { Value::I32(n) => n, _ => unreachable!() };
+                Ok(Some(Value::I32(n * -2)))
+            }));
+
+            for _ in 0..3 {
+                server.serve_one().unwrap();
+            }
+        });
+
+        let mut client = Client::new(client_chan);
+
+        // Forces `enumerate` to run first, negotiating `Encoding::Compact`.
+        client.procedures().unwrap();
+        assert_eq!(client.encoding(), Encoding::Compact);
+
+        match client.call(0, &[Value::I32(-123456)]).unwrap() {
+            Some(Value::I32(n)) => assert_eq!(n, 246912),
+            other => panic!("unexpected call result: {:?}", other.map(|v| v.to_string())),
+        }
+
+        server_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! Sans-IO core of the µRPC client protocol.
+//!
+//! `ClientState` performs no I/O of its own. Instead of blocking on a channel, it hands back a
+//! [`Step`](enum.Step.html) describing what the driver should do next (write some bytes, or read
+//! at least one more) until the exchange is done. `Client` drives this internally for its
+//! blocking methods; advanced users who want to run µRPC from a non-blocking `mio`/tokio event
+//! loop can drive it by hand instead, feeding bytes as they arrive from a non-blocking socket
+//! without losing the exchange's place in the protocol across `WouldBlock`s.
+
+use {Encoding, Error, Procedure, Request, Result, Type, Value};
+
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+
+use std::io::{self, Cursor};
+
+/// What a [`ClientState`](struct.ClientState.html) needs its driver to do next.
+pub enum Step<T> {
+    /// Write this buffer to the channel, then report back how many bytes actually made it out
+    /// via `ClientState::wrote` (a short write is fine, the state machine will ask again for the
+    /// rest).
+    NeedWrite(Vec<u8>),
+    /// Read at least one more byte from the channel and hand it to `ClientState::feed`.
+    NeedRead,
+    /// The exchange finished with this result.
+    Done(Result<T>),
+}
+
+/// Non-blocking, sans-IO state machine driving a single µRPC request/response exchange (the
+/// `Version` handshake, an `Enumerate`, or a `Call`).
+///
+/// This doesn't touch any channel; see the module documentation for how it's meant to be driven.
+pub struct ClientState<T> {
+    to_write: Vec<u8>,
+    written: usize,
+    read_buf: Vec<u8>,
+    parse: Box<Fn(&mut Cursor<&[u8]>) -> Result<T>>,
+}
+
+impl<T> ClientState<T> {
+    /// Advances the state machine and returns what the driver needs to do next.
+    ///
+    /// Safe to call repeatedly without feeding new data in between: it re-parses everything fed
+    /// so far each time, so it never loses its place in the protocol across a `WouldBlock`.
+    pub fn step(&mut self) -> Step<T> {
+        if self.written < self.to_write.len() {
+            return Step::NeedWrite(self.to_write[self.written..].to_vec());
+        }
+
+        let mut cursor = Cursor::new(&self.read_buf[..]);
+        match (self.parse)(&mut cursor) {
+            Ok(val) => Step::Done(Ok(val)),
+            Err(Error::IoError(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => Step::NeedRead,
+            Err(e) => Step::Done(Err(e)),
+        }
+    }
+
+    /// Reports that `n` bytes of the buffer handed out by the last `Step::NeedWrite` were
+    /// written to the channel.
+    pub fn wrote(&mut self, n: usize) {
+        self.written += n;
+    }
+
+    /// Feeds bytes just read from the channel to the state machine.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.read_buf.extend_from_slice(bytes);
+    }
+}
+
+impl ClientState<(u8, Encoding)> {
+    /// Builds the state machine for the `Version` handshake, returning the Server's reported
+    /// version byte and its negotiated `Value` wire encoding.
+    pub fn version_handshake() -> Result<Self> {
+        let mut to_write = Vec::new();
+        to_write.write_u8(Request::Version as u8)?;
+
+        Ok(ClientState {
+            to_write: to_write,
+            written: 0,
+            read_buf: Vec::new(),
+            parse: Box::new(|r| {
+                match r.read_u8()? {
+                    0x00 => {}
+                    _ => return Err(Error::ProtocolError {
+                        description: "invalid result of infallible request",
+                    }),
+                }
+
+                let server_version = r.read_u8()?;
+                let mode = Encoding::read(r)?;
+                Ok((server_version, mode))
+            }),
+        })
+    }
+}
+
+impl ClientState<Vec<Procedure>> {
+    /// Builds the state machine for an `Enumerate` request, returning the Server's exported
+    /// procedures.
+    pub fn enumerate() -> Result<Self> {
+        let mut to_write = Vec::new();
+        to_write.write_u8(Request::Enumerate as u8)?;
+
+        Ok(ClientState {
+            to_write: to_write,
+            written: 0,
+            read_buf: Vec::new(),
+            parse: Box::new(|r| {
+                match r.read_u8()? {
+                    0x00 => {}
+                    _ => return Err(Error::ProtocolError {
+                        description: "invalid result of infallible request",
+                    }),
+                }
+
+                let num_procs = r.read_u16::<NetworkEndian>()?;
+                let mut procs = Vec::with_capacity(num_procs as usize);
+                for i in 0..num_procs {
+                    let byte0 = r.read_u8()?;
+                    let has_return_value = byte0 & 0x80 != 0;
+                    let num_params = byte0 & 0x7f;
+
+                    let return_type = if has_return_value {
+                        Some(Type::read(r)?)
+                    } else {
+                        None
+                    };
+
+                    let mut params = Vec::with_capacity(num_params as usize);
+                    for _ in 0..num_params {
+                        params.push(Type::read(r)?);
+                    }
+
+                    procs.push(Procedure::new(i, params, return_type));
+                }
+
+                Ok(procs)
+            }),
+        })
+    }
+}
+
+impl ClientState<Option<Value>> {
+    /// Builds the state machine for a `Call` request to procedure `id`, expecting a return value
+    /// of `return_type` (if any), with arguments and return value encoded using `mode`.
+    pub fn call(id: u16, arguments: &[Value], return_type: Option<Type>, mode: Encoding) -> Result<Self> {
+        let mut to_write = Vec::new();
+        to_write.write_u8(Request::Call as u8)?;
+        to_write.write_u16::<NetworkEndian>(id)?;
+        for arg in arguments {
+            arg.write(mode, &mut to_write)?;
+        }
+
+        Ok(ClientState {
+            to_write: to_write,
+            written: 0,
+            read_buf: Vec::new(),
+            parse: Box::new(move |r| {
+                match r.read_u8()? {
+                    0x00 => {}
+                    0x01 => return Err(Error::GenericError),
+                    _ => return Err(Error::ProtocolError {
+                        description: "invalid result byte",
+                    }),
+                }
+
+                match return_type {
+                    Some(ref ty) => Ok(Some(Value::read(ty, mode, r)?)),
+                    None => Ok(None),
+                }
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientState, Step};
+    use {Encoding, Type, Value};
+
+    /// Drives a `ClientState` by hand, simulating a non-blocking socket: short writes, and
+    /// `NeedRead` steps that come back with no new data fed (as if the read returned
+    /// `WouldBlock`) before the rest of the response eventually trickles in one byte at a time.
+    #[test]
+    fn drives_past_simulated_would_block() {
+        let mut state = ClientState::call(7, &[Value::U8(41)], Some(Type::U8), Encoding::Fixed).unwrap();
+
+        // Request byte(1) + id(2) + argument(1) = 4 bytes. Simulate a short write that only gets
+        // part of it out before the socket would have blocked.
+        let to_write = match state.step() {
+            Step::NeedWrite(buf) => buf,
+            _ => panic!("expected NeedWrite"),
+        };
+        assert_eq!(to_write.len(), 4);
+        state.wrote(1);
+
+        let rest = match state.step() {
+            Step::NeedWrite(buf) => buf,
+            _ => panic!("expected NeedWrite for the rest of the request"),
+        };
+        assert_eq!(rest, to_write[1..]);
+        state.wrote(rest.len());
+
+        // Everything's been written; a real driver would now try to read a response and hit
+        // `WouldBlock`. Calling `step` again without feeding anything must not lose track of
+        // where the exchange is.
+        assert!(matches!(state.step(), Step::NeedRead));
+        assert!(matches!(state.step(), Step::NeedRead));
+
+        // The result byte arrives first, on its own.
+        state.feed(&[0x00]);
+        assert!(matches!(state.step(), Step::NeedRead));
+
+        // Then the U8 return value arrives.
+        state.feed(&[41]);
+        match state.step() {
+            Step::Done(Ok(Some(Value::U8(val)))) => assert_eq!(val, 41),
+            other => panic!("unexpected final step: {}", match other {
+                Step::Done(Ok(v)) => format!("Done(Ok({:?}))", v.map(|v| v.to_string())),
+                Step::Done(Err(e)) => format!("Done(Err({}))", e),
+                Step::NeedRead => "NeedRead".to_owned(),
+                Step::NeedWrite(_) => "NeedWrite".to_owned(),
+            }),
+        }
+    }
+}